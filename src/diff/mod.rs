@@ -1,44 +1,370 @@
 use crate::lcs;
+use regex::Regex;
 use serde_json::{map::Map, Value};
 
 use std::{
     cmp::{max, min},
     collections::HashSet,
-    fmt,
-    ops::{Deref, DerefMut},
+    fmt::{self, Write},
+    mem,
+    ops::{AddAssign, Deref, Range},
+    str::FromStr,
 };
 
+/// An operation in a diff at a dotted path like `.users.0.name`.
 #[derive(Debug, PartialEq)]
 pub enum Op {
+    /// A value that is only in the new document.
     Added(JsonPath, Value),
+    /// A value that is different in the new document: the old value and
+    /// the new one.
     Changed(JsonPath, Value, Value),
+    /// A value that is only in the old document.
     Removed(JsonPath, Value),
 }
 
+/// A dotted path like `.users.0.name`. The root of the document is `""`.
 pub type JsonPath = String;
 pub type JsonDiffContents = Vec<Op>;
 
-#[derive(Debug)]
-pub struct JsonDiff(JsonDiffContents);
+/// The operations that turn one document into another, in the order the
+/// text output shows them.
+#[derive(Debug, Default)]
+pub struct JsonDiff {
+    ops: JsonDiffContents,
+    // The JSON Pointer of each operation. The dotted path doesn't tell a key
+    // with a dot in it from nested keys.
+    pointers: Vec<String>,
+}
+
+/// Counts of each kind of operation in one or more diffs.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiffStats {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+}
+
+/// A change at one location in a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The dotted path like `.users.0.name` that the text output shows.
+    pub path: JsonPath,
+    /// The location as a JSON Pointer (RFC 6901) like `/users/0/name`.
+    pub pointer: String,
+    /// The value before the change. `None` for added values.
+    pub old: Option<Value>,
+    /// The value after the change. `None` for removed values.
+    pub new: Option<Value>,
+}
+
+/// The changes between two documents grouped by kind.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Diff {
+    pub added: Vec<Change>,
+    pub removed: Vec<Change>,
+    pub changed: Vec<Change>,
+}
+
+impl Change {
+    /// The names of the old and new types if the value changed from one
+    /// non-null type to another, like `("string", "object")`.
+    pub fn type_change(&self) -> Option<(&'static str, &'static str)> {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => changed_types(old, new),
+            _ => None,
+        }
+    }
+}
+
+impl Diff {
+    /// Whether there are no changes of any kind.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of changes of all kinds.
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len() + self.changed.len()
+    }
+}
 
 impl fmt::Display for JsonDiff {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_text(None))
+    }
+}
+
+impl Op {
+    /// The dotted path of the value the operation is about.
+    pub fn path(&self) -> &JsonPath {
+        match self {
+            Op::Added(path, _) => path,
+            Op::Changed(path, _, _) => path,
+            Op::Removed(path, _) => path,
+        }
+    }
+
+    /// The names of the old and new types when a value changes from one
+    /// non-null type to another.
+    pub fn type_change(&self) -> Option<(&'static str, &'static str)> {
+        match self {
+            Op::Changed(_, a, b) => changed_types(a, b),
+            _ => None,
+        }
+    }
+
+    /// An object like `{"op": "changed", "path": ".a", "old": 1, "new": 2}`
+    /// as in `--format json`.
+    pub fn to_json(&self) -> Value {
+        let mut obj = Map::new();
+
+        match self {
+            Op::Added(path, value) => {
+                obj.insert("op".to_string(), "added".into());
+                obj.insert("path".to_string(), path.clone().into());
+                obj.insert("new".to_string(), value.clone());
+            }
+            Op::Changed(path, from_value, to_value) => {
+                obj.insert("op".to_string(), "changed".into());
+                obj.insert("path".to_string(), path.clone().into());
+                obj.insert("old".to_string(), from_value.clone());
+                obj.insert("new".to_string(), to_value.clone());
+
+                if let Some((from_type, to_type)) = self.type_change() {
+                    obj.insert("old_type".to_string(), from_type.into());
+                    obj.insert("new_type".to_string(), to_type.into());
+                }
+            }
+            Op::Removed(path, value) => {
+                obj.insert("op".to_string(), "removed".into());
+                obj.insert("path".to_string(), path.clone().into());
+                obj.insert("old".to_string(), value.clone());
+            }
+        }
+
+        Value::Object(obj)
+    }
+}
+
+impl JsonDiff {
+    /// An array with an object for each operation like `Op::to_json`.
+    pub fn to_json(&self) -> Value {
+        Value::Array(self.iter().map(Op::to_json).collect())
+    }
+
+    /// Format as text with a line for each operation like `Display`.
+    /// Objects and arrays more than `max_depth` levels deep in the document
+    /// are shown as a summary like `{ ... 42 keys }`.
+    pub fn to_text(&self, max_depth: Option<usize>) -> String {
+        let mut s = String::new();
+
         for op in self.iter() {
+            let depth = path_depth(op.path());
+            let show = |value: &Value| match max_depth {
+                Some(max) => collapsed(value, max.saturating_sub(depth)),
+                None => value.to_string(),
+            };
+
             match op {
                 Op::Added(path, value) => {
-                    writeln!(f, "+ {}: {}", path, value).unwrap();
+                    writeln!(s, "+ {}: {}", path, show(value)).unwrap();
                 }
                 Op::Changed(path, from_value, to_value) => {
-                    writeln!(f, "{}: {} -> {}", path, from_value, to_value)
-                        .unwrap();
+                    match op.type_change() {
+                        Some((from_type, to_type)) => writeln!(
+                            s,
+                            "~ {}: {} -> {} ({} -> {})",
+                            path,
+                            from_type,
+                            to_type,
+                            show(from_value),
+                            show(to_value)
+                        ),
+                        None => writeln!(
+                            s,
+                            "{}: {} -> {}",
+                            path,
+                            show(from_value),
+                            show(to_value)
+                        ),
+                    }
+                    .unwrap();
                 }
                 Op::Removed(path, value) => {
-                    writeln!(f, "- {}: {}", path, value).unwrap();
+                    writeln!(s, "- {}: {}", path, show(value)).unwrap();
+                }
+            }
+        }
+
+        s
+    }
+
+    /// Format like a unified diff with a hunk for each path.
+    pub fn unified(&self) -> String {
+        let mut s = String::new();
+
+        for op in self.iter() {
+            s.push_str(&format!("@@ {} @@\n", op.path()));
+
+            match op {
+                Op::Added(_, value) => s.push_str(&format!("+{}\n", value)),
+                Op::Changed(_, from_value, to_value) => {
+                    s.push_str(&format!("-{}\n+{}\n", from_value, to_value))
+                }
+                Op::Removed(_, value) => s.push_str(&format!("-{}\n", value)),
+            }
+        }
+
+        s
+    }
+
+    /// Group the operations by kind into a `Diff` with JSON Pointers.
+    pub fn structured(&self) -> Diff {
+        let mut result = Diff::default();
+
+        for (op, pointer) in self.iter().zip(&self.pointers) {
+            let list = match op {
+                Op::Added(..) => &mut result.added,
+                Op::Changed(..) => &mut result.changed,
+                Op::Removed(..) => &mut result.removed,
+            };
+            let (old, new) = match op {
+                Op::Added(_, value) => (None, Some(value.clone())),
+                Op::Changed(_, old, new) => {
+                    (Some(old.clone()), Some(new.clone()))
                 }
+                Op::Removed(_, value) => (Some(value.clone()), None),
+            };
+
+            list.push(Change {
+                path: op.path().clone(),
+                pointer: pointer.clone(),
+                old,
+                new,
+            });
+        }
+
+        result
+    }
+
+    /// Count the operations of each kind.
+    pub fn stats(&self) -> DiffStats {
+        let mut stats = DiffStats::default();
+
+        for op in self.iter() {
+            match op {
+                Op::Added(..) => stats.added += 1,
+                Op::Changed(..) => stats.changed += 1,
+                Op::Removed(..) => stats.removed += 1,
             }
         }
 
-        fmt::Result::Ok(())
+        stats
+    }
+
+    /// Sort the operations by path so the order doesn't depend on the order
+    /// of keys in the data.
+    /// Array indices sort by number, so `.a.2` comes before `.a.10`.
+    pub fn sort_by_path(&mut self) {
+        let mut entries = self.take_entries();
+        entries.sort_by(|(a, _), (b, _)| {
+            path_key(a.path()).cmp(&path_key(b.path()))
+        });
+        (self.ops, self.pointers) = entries.into_iter().unzip();
+    }
+
+    /// A copy with the strings in the values shortened for display like
+    /// with `truncate_strings`.
+    pub fn truncated(&self, max: usize) -> JsonDiff {
+        let values = |value: &Value| truncate_strings(value, max);
+
+        JsonDiff {
+            ops: self
+                .iter()
+                .map(|op| match op {
+                    Op::Added(path, value) => {
+                        Op::Added(path.clone(), values(value))
+                    }
+                    Op::Changed(path, a, b) => {
+                        Op::Changed(path.clone(), values(a), values(b))
+                    }
+                    Op::Removed(path, value) => {
+                        Op::Removed(path.clone(), values(value))
+                    }
+                })
+                .collect(),
+            pointers: self.pointers.clone(),
+        }
+    }
+
+    /// Whether an operation changes the value at the path of a pattern.
+    /// That is the case for an operation at, below, or above the path, like
+    /// `.status` for `status.phase` when the whole object was replaced.
+    pub fn touches(&self, patterns: &[PathPattern]) -> bool {
+        self.iter().any(|op| {
+            let path = op.path();
+            patterns.iter().any(|pattern| {
+                pattern.in_subtree(path) || pattern.is_below(path)
+            })
+        })
+    }
+
+    // A diff with pointers made from the paths of the operations, as if no
+    // key had a dot in it.
+    #[cfg(test)]
+    fn from_ops(ops: JsonDiffContents) -> JsonDiff {
+        let pointers = ops
+            .iter()
+            .map(|op| {
+                op.path()
+                    .split('.')
+                    .skip(1)
+                    .map(|token| format!("/{}", escape_pointer_token(token)))
+                    .collect()
+            })
+            .collect();
+
+        JsonDiff { ops, pointers }
+    }
+
+    fn push(&mut self, location: &Location, op: Op) {
+        self.ops.push(op);
+        self.pointers.push(location.pointer.clone());
+    }
+
+    fn added(&mut self, location: &Location, value: &Value) {
+        self.push(location, Op::Added(location.path.clone(), value.clone()));
+    }
+
+    fn changed(&mut self, location: &Location, a: &Value, b: &Value) {
+        let op = Op::Changed(location.path.clone(), a.clone(), b.clone());
+        self.push(location, op);
+    }
+
+    fn removed(&mut self, location: &Location, value: &Value) {
+        self.push(location, Op::Removed(location.path.clone(), value.clone()));
+    }
+
+    fn retain(&mut self, keep: impl Fn(&Op) -> bool) {
+        let entries = self.take_entries();
+        (self.ops, self.pointers) =
+            entries.into_iter().filter(|(op, _)| keep(op)).unzip();
+    }
+
+    fn take_entries(&mut self) -> Vec<(Op, String)> {
+        mem::take(&mut self.ops)
+            .into_iter()
+            .zip(mem::take(&mut self.pointers))
+            .collect()
+    }
+}
+
+impl AddAssign for DiffStats {
+    fn add_assign(&mut self, other: Self) {
+        self.added += other.added;
+        self.changed += other.changed;
+        self.removed += other.removed;
     }
 }
 
@@ -46,47 +372,241 @@ impl Deref for JsonDiff {
     type Target = JsonDiffContents;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.ops
+    }
+}
+
+/// A dotted path like `meta.timestamp` that matches a single path in a diff.
+/// A trailing `.*` like in `meta.*` makes it match the whole subtree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathPattern {
+    path: JsonPath,
+    subtree: bool,
+}
+
+impl PathPattern {
+    /// Whether `path` is the path of the pattern or, for a pattern with
+    /// `.*`, is below it.
+    pub fn matches(&self, path: &str) -> bool {
+        if self.subtree {
+            self.in_subtree(path)
+        } else {
+            path == self.path
+        }
+    }
+
+    /// Whether `path` is the path of the pattern or is below it regardless of
+    /// the wildcard.
+    pub fn in_subtree(&self, path: &str) -> bool {
+        match path.strip_prefix(&self.path) {
+            Some(rest) => rest.is_empty() || rest.starts_with('.'),
+            None => false,
+        }
+    }
+
+    /// Whether the path of the pattern is strictly below `path`.
+    pub fn is_below(&self, path: &str) -> bool {
+        match self.path.strip_prefix(path) {
+            Some(rest) => rest.starts_with('.'),
+            None => false,
+        }
+    }
+}
+
+impl fmt::Display for PathPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.path.as_str(), self.subtree) {
+            ("", true) => write!(f, "*"),
+            (path, true) => write!(f, "{}.*", path),
+            (path, false) => write!(f, "{}", path),
+        }
+    }
+}
+
+impl FromStr for PathPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, subtree) = match s {
+            "*" | ".*" => ("", true),
+            _ => match s.strip_suffix(".*") {
+                Some(prefix) => (prefix, true),
+                None => (s, false),
+            },
+        };
+
+        if path.contains('*') {
+            return Err("only a trailing \".*\" wildcard is supported".into());
+        }
+
+        let path = if path.is_empty() || path.starts_with('.') {
+            path.to_string()
+        } else {
+            format!(".{}", path)
+        };
+
+        Ok(PathPattern { path, subtree })
+    }
+}
+
+/// An array path and the field its elements are matched by, written as
+/// `users=id`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArrayKey {
+    /// The arrays the key is for.
+    pub path: PathPattern,
+    /// The field of their elements to match them by.
+    pub field: String,
+}
+
+impl FromStr for ArrayKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.rsplit_once('=') {
+            Some((path, field)) if !field.is_empty() => Ok(ArrayKey {
+                path: path.parse()?,
+                field: field.to_string(),
+            }),
+            _ => Err("expected \"path=field\"".into()),
+        }
     }
 }
 
-impl DerefMut for JsonDiff {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+/// Options for `diff_with` and `json_patch_with`. The default compares
+/// documents exactly.
+#[derive(Clone, Debug, Default)]
+pub struct DiffOptions {
+    /// Changes at paths that match any of these patterns are left out.
+    pub ignore: Vec<PathPattern>,
+
+    /// Numbers are equal when the absolute difference between them is at most
+    /// `epsilon` or when it is at most `epsilon_rel` times the larger absolute
+    /// value.
+    pub epsilon: Option<f64>,
+    /// See `epsilon`.
+    pub epsilon_rel: Option<f64>,
+
+    /// Compare arrays as multisets of structurally equal values and ignore
+    /// the order of their elements.
+    pub array_as_set: bool,
+
+    /// Match the elements of arrays at these paths by the value of a field
+    /// instead of by position. This takes precedence over `array_as_set`.
+    pub array_keys: Vec<ArrayKey>,
+
+    /// Changes where both the old and the new value match one of these are
+    /// left out. Strings are matched without quotes.
+    pub ignore_values: Vec<Regex>,
+}
+
+impl DiffOptions {
+    fn ignores(&self, path: &str) -> bool {
+        self.ignore.iter().any(|pattern| pattern.matches(path))
+    }
+
+    fn array_key(&self, path: &str) -> Option<&str> {
+        self.array_keys
+            .iter()
+            .find(|key| key.path.matches(path))
+            .map(|key| key.field.as_str())
+    }
+
+    fn ignores_values(&self, a: &Value, b: &Value) -> bool {
+        let text = |value: &Value| match value {
+            Value::String(s) => s.clone(),
+            _ => value.to_string(),
+        };
+        let (a, b) = (text(a), text(b));
+
+        self.ignore_values
+            .iter()
+            .any(|regex| regex.is_match(&a) && regex.is_match(&b))
+    }
+
+    fn numbers_within_tolerance(&self, a: &Value, b: &Value) -> bool {
+        let (a, b) = match (a.as_f64(), b.as_f64()) {
+            (Some(a), Some(b)) if a.is_finite() && b.is_finite() => (a, b),
+            _ => return false,
+        };
+        let delta = (a - b).abs();
+
+        if let Some(epsilon) = self.epsilon {
+            if delta <= epsilon {
+                return true;
+            }
+        }
+
+        if let Some(epsilon_rel) = self.epsilon_rel {
+            if delta <= epsilon_rel * a.abs().max(b.abs()) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn values_equal(&self, a: &Value, b: &Value) -> bool {
+        a == b || self.numbers_within_tolerance(a, b)
     }
 }
 
+/// Compute the operations that turn `prev` into `current`. `None` stands
+/// for a missing document, so a document that appears is added whole and one
+/// that disappears is removed whole.
 pub fn diff(prev: &Option<Value>, current: &Option<Value>) -> JsonDiff {
-    let mut diff = JsonDiff(Vec::new());
-    let prefix: JsonPath = "".to_string();
+    diff_with(prev, current, &DiffOptions::default())
+}
+
+/// Like `diff` but with the comparison and the changes left out decided by
+/// `options`.
+pub fn diff_with(
+    prev: &Option<Value>,
+    current: &Option<Value>,
+    options: &DiffOptions,
+) -> JsonDiff {
+    let mut diff = diff_values(prev, current, options);
+
+    if !options.ignore.is_empty() {
+        diff.retain(|op| !options.ignores(op.path()));
+    }
+
+    if options.epsilon.is_some() || options.epsilon_rel.is_some() {
+        diff.retain(|op| match op {
+            Op::Changed(_, a, b) => !options.numbers_within_tolerance(a, b),
+            _ => true,
+        });
+    }
+
+    if !options.ignore_values.is_empty() {
+        diff.retain(|op| match op {
+            Op::Changed(_, a, b) => !options.ignores_values(a, b),
+            _ => true,
+        });
+    }
+
+    diff
+}
+
+fn diff_values(
+    prev: &Option<Value>,
+    current: &Option<Value>,
+    options: &DiffOptions,
+) -> JsonDiff {
+    let mut diff = JsonDiff::default();
+    let root = Location::root();
 
     match (prev, current) {
         (None, None) => {}
+        // A document that disappears or appears is removed or added whole.
         (Some(v), None) => {
-            diff_helper(&mut diff, &prefix, &v, &Value::Null);
-            // Remove the placeholder Null.
-            match &diff[..] {
-                [Op::Changed(p, v, Value::Null)] => {
-                    diff[0] = Op::Removed(p.to_string(), v.clone());
-                }
-                _ => {
-                    diff.pop();
-                }
-            }
+            diff.removed(&root, v);
         }
         (None, Some(v)) => {
-            diff_helper(&mut diff, &prefix, &Value::Null, &v);
-            match &diff[..] {
-                [Op::Changed(p, Value::Null, v)] => {
-                    diff[0] = Op::Added(p.to_string(), v.clone());
-                }
-                _ => {
-                    diff.remove(0);
-                }
-            }
+            diff.added(&root, v);
         }
         (Some(v1), Some(v2)) => {
-            diff_helper(&mut diff, &prefix, &v1, &v2);
+            diff_helper(&mut diff, &root, v1, v2, options);
         }
     }
 
@@ -95,166 +615,657 @@ pub fn diff(prev: &Option<Value>, current: &Option<Value>) -> JsonDiff {
 
 fn diff_helper(
     acc: &mut JsonDiff,
-    prefix: &JsonPath,
+    location: &Location,
     prev: &Value,
     current: &Value,
+    options: &DiffOptions,
 ) {
     match (prev, current) {
         (Value::Array(a), Value::Array(b)) => {
-            diff_array(acc, prefix, a, b);
+            if let Some(field) = options.array_key(&location.path) {
+                diff_array_keyed(acc, location, a, b, field, options);
+            } else if options.array_as_set {
+                diff_array_set(acc, location, a, b);
+            } else {
+                diff_array(acc, location, a, b);
+            }
         }
         (Value::Object(a), Value::Object(b)) => {
-            diff_obj(acc, prefix, a, b);
+            diff_obj(acc, location, a, b, options);
+        }
+        // Report a change of type as a single change instead of what is
+        // removed and added inside the value.
+        (a, b) if changed_types(a, b).is_some() => {
+            acc.changed(location, a, b);
         }
         (Value::Array(a), b) => {
-            diff_array(acc, prefix, a, &Vec::new());
-            acc.push(Op::Added(prefix.clone(), b.clone()));
+            diff_array(acc, location, a, &Vec::new());
+            acc.added(location, b);
         }
         (Value::Object(a), b) => {
-            diff_obj(acc, prefix, a, &Map::new());
-            acc.push(Op::Added(prefix.clone(), b.clone()));
+            diff_obj(acc, location, a, &Map::new(), options);
+            acc.added(location, b);
         }
         (a, Value::Array(b)) => {
-            acc.push(Op::Removed(prefix.clone(), a.clone()));
-            diff_array(acc, prefix, &Vec::new(), b);
+            acc.removed(location, a);
+            diff_array(acc, location, &Vec::new(), b);
         }
         (a, Value::Object(b)) => {
-            acc.push(Op::Removed(prefix.clone(), a.clone()));
-            diff_obj(acc, prefix, &Map::new(), b);
+            acc.removed(location, a);
+            diff_obj(acc, location, &Map::new(), b, options);
         }
         (a, b) => {
             if a != b {
-                acc.push(Op::Changed(prefix.to_string(), a.clone(), b.clone()));
+                acc.changed(location, a, b);
             }
         }
     }
 }
 
-fn diff_array(
-    acc: &mut JsonDiff,
-    prefix: &JsonPath,
-    a: &Vec<Value>,
-    b: &Vec<Value>,
-) {
-    let lcs = lcs::lcs(a, b);
+// Return the gaps between the elements of the longest common subsequence of
+// `a` and `b` as pairs of index ranges into `a` and `b`.
+fn array_gaps(a: &[Value], b: &[Value]) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut gaps = Vec::new();
 
     let mut a_prev: usize = 0;
     let mut b_prev: usize = 0;
 
-    for (a_i, b_i) in &lcs {
-        let changed_range = max(a_prev, b_prev)..min(*a_i, *b_i);
+    for (a_i, b_i) in lcs::lcs(a, b) {
+        gaps.push((a_prev..a_i, b_prev..b_i));
+
+        a_prev = a_i + 1;
+        b_prev = b_i + 1;
+    }
+
+    gaps.push((a_prev..a.len(), b_prev..b.len()));
+
+    gaps
+}
+
+// Return the range of indices in a gap where an element of `a` is replaced
+// with an element of `b`.
+fn changed_range(
+    a_range: &Range<usize>,
+    b_range: &Range<usize>,
+) -> Range<usize> {
+    max(a_range.start, b_range.start)..min(a_range.end, b_range.end)
+}
+
+fn diff_array(
+    acc: &mut JsonDiff,
+    location: &Location,
+    a: &[Value],
+    b: &[Value],
+) {
+    for (a_range, b_range) in array_gaps(a, b) {
+        let changed_range = changed_range(&a_range, &b_range);
         for i in changed_range.clone() {
-            acc.push(Op::Changed(
-                format!("{}.{}", prefix, i),
-                a[i].clone(),
-                b[i].clone(),
-            ));
+            acc.changed(&location.index(i), &a[i], &b[i]);
         }
-        for i in a_prev..*a_i {
+        for i in a_range {
             if changed_range.contains(&i) {
                 continue;
             };
-            acc.push(Op::Removed(format!("{}.{}", prefix, i), a[i].clone()));
+            acc.removed(&location.index(i), &a[i]);
         }
-        for i in b_prev..*b_i {
+        for i in b_range {
             if changed_range.contains(&i) {
                 continue;
             };
-            acc.push(Op::Added(format!("{}.{}", prefix, i), b[i].clone()));
+            acc.added(&location.index(i), &b[i]);
         }
+    }
+}
 
-        a_prev = a_i + 1;
-        b_prev = b_i + 1;
+// Return the indices of the elements of `a` that have no equal element in `b`
+// and the other way around. Each element is matched at most once.
+fn unmatched_elements(a: &[Value], b: &[Value]) -> (Vec<usize>, Vec<usize>) {
+    let mut matched = vec![false; b.len()];
+    let mut a_unmatched = Vec::new();
+
+    for (i, a_v) in a.iter().enumerate() {
+        match (0..b.len()).find(|&j| !matched[j] && *a_v == b[j]) {
+            Some(j) => matched[j] = true,
+            None => a_unmatched.push(i),
+        }
     }
 
-    let changed_range = max(a_prev, b_prev)..min(a.len(), b.len());
-    for i in changed_range.clone() {
-        acc.push(Op::Changed(
-            format!("{}.{}", prefix, i),
-            a[i].clone(),
-            b[i].clone(),
-        ));
+    let b_unmatched = (0..b.len()).filter(|&j| !matched[j]).collect();
+
+    (a_unmatched, b_unmatched)
+}
+
+fn diff_array_set(
+    acc: &mut JsonDiff,
+    location: &Location,
+    a: &[Value],
+    b: &[Value],
+) {
+    let (removed, added) = unmatched_elements(a, b);
+
+    for i in removed {
+        acc.removed(&location.index(i), &a[i]);
     }
-    for i in a_prev..a.len() {
-        if changed_range.contains(&i) {
-            continue;
-        };
-        acc.push(Op::Removed(format!("{}.{}", prefix, i), a[i].clone()));
+    for j in added {
+        acc.added(&location.index(j), &b[j]);
     }
-    for i in b_prev..b.len() {
-        if changed_range.contains(&i) {
-            continue;
-        };
-        acc.push(Op::Added(format!("{}.{}", prefix, i), b[i].clone()));
+}
+
+// Pair up the elements of `a` and `b` that are objects with equal values of
+// `field`. Elements without the field match only structurally equal elements.
+// Return the pairs and the unmatched indices in `a` and `b`.
+fn keyed_elements(
+    a: &[Value],
+    b: &[Value],
+    field: &str,
+) -> (Vec<(usize, usize)>, Vec<usize>, Vec<usize>) {
+    let key = |v: &Value| v.as_object().and_then(|obj| obj.get(field)).cloned();
+
+    let mut matched = vec![false; b.len()];
+    let mut pairs = Vec::new();
+    let mut a_unmatched = Vec::new();
+
+    for (i, a_v) in a.iter().enumerate() {
+        let a_key = key(a_v);
+        let found = (0..b.len()).find(|&j| {
+            !matched[j]
+                && match &a_key {
+                    Some(_) => key(&b[j]) == a_key,
+                    None => key(&b[j]).is_none() && *a_v == b[j],
+                }
+        });
+
+        match found {
+            Some(j) => {
+                matched[j] = true;
+                pairs.push((i, j));
+            }
+            None => a_unmatched.push(i),
+        }
+    }
+
+    let b_unmatched = (0..b.len()).filter(|&j| !matched[j]).collect();
+
+    (pairs, a_unmatched, b_unmatched)
+}
+
+// Changes inside matched elements are reported at their index in `b`.
+fn diff_array_keyed(
+    acc: &mut JsonDiff,
+    location: &Location,
+    a: &[Value],
+    b: &[Value],
+    field: &str,
+    options: &DiffOptions,
+) {
+    let (pairs, removed, added) = keyed_elements(a, b, field);
+
+    for (i, j) in pairs {
+        diff_helper(acc, &location.index(j), &a[i], &b[j], options);
+    }
+    for i in removed {
+        acc.removed(&location.index(i), &a[i]);
+    }
+    for j in added {
+        acc.added(&location.index(j), &b[j]);
     }
 }
 
 fn diff_obj(
     acc: &mut JsonDiff,
-    prefix: &JsonPath,
+    location: &Location,
     a: &Map<String, Value>,
     b: &Map<String, Value>,
+    options: &DiffOptions,
 ) {
     let mut a_keys: HashSet<String> = HashSet::new();
 
     for (k, a_v) in a {
         a_keys.insert(k.clone());
 
-        let new_prefix = format!("{}.{}", prefix, k);
+        let new_location = location.key(k);
         match b.get(k) {
             Some(b_v) => {
-                diff_helper(acc, &new_prefix, a_v, b_v);
+                diff_helper(acc, &new_location, a_v, b_v, options);
             }
             None => {
-                acc.push(Op::Removed(new_prefix, a_v.clone()));
+                acc.removed(&new_location, a_v);
             }
         }
     }
 
     for (k, b_v) in b {
-        let new_prefix = format!("{}.{}", prefix, k);
         if !a_keys.contains(k) {
-            acc.push(Op::Added(new_prefix, b_v.clone()));
+            acc.added(&location.key(k), b_v);
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Sort the keys of every object in `value`. With `sort_arrays`, also sort
+/// array elements by their JSON text, so equal sets of elements come out the
+/// same.
+pub fn canonical(value: &Value, sort_arrays: bool) -> Value {
+    match value {
+        Value::Object(obj) => {
+            let mut entries = obj.iter().collect::<Vec<_>>();
+            entries.sort_by_key(|(k, _)| *k);
 
-    fn vec_value_string(strs: &[&str]) -> Vec<Value> {
-        let mut v: Vec<Value> = Vec::new();
-        for s in strs {
-            v.push(Value::String(s.to_string()));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), canonical(v, sort_arrays)))
+                    .collect(),
+            )
         }
+        Value::Array(arr) => {
+            let mut items = arr
+                .iter()
+                .map(|v| canonical(v, sort_arrays))
+                .collect::<Vec<_>>();
+            if sort_arrays {
+                items.sort_by_cached_key(|v| v.to_string());
+            }
 
-        v
+            Value::Array(items)
+        }
+        _ => value.clone(),
     }
+}
 
-    #[test]
-    fn test_diff_array_1() {
-        let a = vec_value_string(&["foo", "bar"]);
-        let b = vec_value_string(&["foo", "bar", "baz"]);
+/// Shorten the strings in `value` longer than `max` characters to their first
+/// `max` characters followed by the length like `...(12345 bytes)`.
+pub fn truncate_strings(value: &Value, max: usize) -> Value {
+    match value {
+        Value::String(s) => match s.char_indices().nth(max) {
+            Some((end, _)) => {
+                format!("{}...({} bytes)", &s[..end], s.len()).into()
+            }
+            None => value.clone(),
+        },
+        Value::Object(obj) => Value::Object(
+            obj.iter()
+                .map(|(k, v)| (k.clone(), truncate_strings(v, max)))
+                .collect(),
+        ),
+        Value::Array(arr) => {
+            Value::Array(arr.iter().map(|v| truncate_strings(v, max)).collect())
+        }
+        _ => value.clone(),
+    }
+}
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+/// Keep only the subtrees of `value` at the paths of `patterns` and the
+/// objects and arrays that contain them. Array elements that aren't kept are
+/// replaced with `null` so the indices of the rest don't change.
+pub fn select(value: &Value, patterns: &[PathPattern]) -> Value {
+    match select_helper(value, "", patterns) {
+        Some(v) => v,
+        None => Value::Null,
+    }
+}
 
-        assert_eq!(*acc, vec![Op::Added(".2".to_string(), Value::from("baz"))]);
+fn select_helper(
+    value: &Value,
+    path: &str,
+    patterns: &[PathPattern],
+) -> Option<Value> {
+    if patterns.iter().any(|pattern| pattern.in_subtree(path)) {
+        return Some(value.clone());
+    }
+    if !patterns.iter().any(|pattern| pattern.is_below(path)) {
+        return None;
     }
 
-    #[test]
-    fn test_diff_array_2() {
-        let a = vec_value_string(&["foo", "bar", "baz"]);
-        let b = vec_value_string(&["foo", "bar"]);
+    match value {
+        Value::Object(obj) => {
+            let mut selected = Map::new();
+            for (k, v) in obj {
+                let new_path = format!("{}.{}", path, k);
+                if let Some(v) = select_helper(v, &new_path, patterns) {
+                    selected.insert(k.clone(), v);
+                }
+            }
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+            Some(Value::Object(selected))
+        }
+        Value::Array(arr) => {
+            let mut selected = Vec::new();
+            for (i, v) in arr.iter().enumerate() {
+                let new_path = format!("{}.{}", path, i);
+                if let Some(v) = select_helper(v, &new_path, patterns) {
+                    selected.resize(i, Value::Null);
+                    selected.push(v);
+                }
+            }
 
-        assert_eq!(
-            *acc,
-            vec![Op::Removed(".2".to_string(), Value::from("baz"))]
-        );
+            Some(Value::Array(selected))
+        }
+        _ => None,
+    }
+}
+
+// The number of keys and indices in a path like `.a.0.b`.
+fn path_depth(path: &str) -> usize {
+    path.matches('.').count()
+}
+
+// The segments of a path with indices before keys and in numeric order.
+fn path_key(path: &str) -> Vec<(bool, Option<u64>, &str)> {
+    path.split('.')
+        .map(|segment| {
+            let index = segment.parse::<u64>().ok();
+            (index.is_none(), index, segment)
+        })
+        .collect()
+}
+
+// Format `value` as compact JSON with the objects and arrays nested more than
+// `depth` levels in it replaced with a summary.
+fn collapsed(value: &Value, depth: usize) -> String {
+    let plural = |n: usize, word: &str| {
+        format!("{} {}{}", n, word, if n == 1 { "" } else { "s" })
+    };
+
+    match value {
+        Value::Object(obj) if !obj.is_empty() && depth == 0 => {
+            format!("{{ ... {} }}", plural(obj.len(), "key"))
+        }
+        Value::Array(arr) if !arr.is_empty() && depth == 0 => {
+            format!("[ ... {} ]", plural(arr.len(), "item"))
+        }
+        Value::Object(obj) => {
+            let items = obj
+                .iter()
+                .map(|(k, v)| {
+                    format!(
+                        "{}:{}",
+                        Value::from(k.as_str()),
+                        collapsed(v, depth - 1)
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            format!("{{{}}}", items.join(","))
+        }
+        Value::Array(arr) => {
+            let items = arr
+                .iter()
+                .map(|v| collapsed(v, depth - 1))
+                .collect::<Vec<_>>();
+
+            format!("[{}]", items.join(","))
+        }
+        _ => value.to_string(),
+    }
+}
+
+/// The JSON name of the type of `value` like `"string"` or `"object"`.
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+// A value becoming or stopping being `null` isn't a change of type. Fields
+// are often nullable.
+fn changed_types(a: &Value, b: &Value) -> Option<(&'static str, &'static str)> {
+    let (a, b) = (type_name(a), type_name(b));
+
+    if a == b || a == "null" || b == "null" {
+        None
+    } else {
+        Some((a, b))
+    }
+}
+
+// Escape a key for use as a JSON Pointer (RFC 6901) reference token.
+fn escape_pointer_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+// A location in a document both as a `JsonPath` for matching against
+// `DiffOptions` and as a JSON Pointer.
+struct Location {
+    path: JsonPath,
+    pointer: String,
+}
+
+impl Location {
+    fn root() -> Location {
+        Location {
+            path: "".to_string(),
+            pointer: "".to_string(),
+        }
+    }
+
+    fn key(&self, k: &str) -> Location {
+        Location {
+            path: format!("{}.{}", self.path, k),
+            pointer: format!("{}/{}", self.pointer, escape_pointer_token(k)),
+        }
+    }
+
+    fn index(&self, i: usize) -> Location {
+        Location {
+            path: format!("{}.{}", self.path, i),
+            pointer: format!("{}/{}", self.pointer, i),
+        }
+    }
+
+    // The end of an array for appending the element at index `i` of the new
+    // array.
+    fn end(&self, i: usize) -> Location {
+        Location {
+            path: format!("{}.{}", self.path, i),
+            pointer: format!("{}/-", self.pointer),
+        }
+    }
+}
+
+struct JsonPatch<'a> {
+    ops: Vec<Value>,
+    options: &'a DiffOptions,
+}
+
+impl JsonPatch<'_> {
+    fn push(&mut self, op: &str, location: &Location, value: Option<&Value>) {
+        if self.options.ignores(&location.path) {
+            return;
+        }
+
+        let mut obj = Map::new();
+
+        obj.insert("op".to_string(), op.into());
+        obj.insert("path".to_string(), location.pointer.clone().into());
+        if let Some(v) = value {
+            obj.insert("value".to_string(), v.clone());
+        }
+
+        self.ops.push(Value::Object(obj));
+    }
+
+    // Replace `a` with `b` unless the options make them count as the same.
+    fn replace(&mut self, location: &Location, a: &Value, b: &Value) {
+        if !self.options.values_equal(a, b)
+            && !self.options.ignores_values(a, b)
+        {
+            self.push("replace", location, Some(b));
+        }
+    }
+}
+
+/// Compute a JSON Patch (RFC 6902) that transforms `prev` into `current`.
+/// Unlike the output of `diff`, the operations are meant to be applied in
+/// order, so array removals go from the highest index to the lowest.
+pub fn json_patch(prev: &Option<Value>, current: &Option<Value>) -> Value {
+    json_patch_with(prev, current, &DiffOptions::default())
+}
+
+/// Like `json_patch` but with `options` like in `diff_with`.
+pub fn json_patch_with(
+    prev: &Option<Value>,
+    current: &Option<Value>,
+    options: &DiffOptions,
+) -> Value {
+    let mut acc = JsonPatch {
+        ops: Vec::new(),
+        options,
+    };
+    let root = Location::root();
+
+    match (prev, current) {
+        (None, None) => {}
+        (Some(_), None) => {
+            acc.push("remove", &root, None);
+        }
+        (None, Some(v)) => {
+            acc.push("add", &root, Some(v));
+        }
+        (Some(v1), Some(v2)) => {
+            patch_helper(&mut acc, &root, v1, v2);
+        }
+    }
+
+    Value::Array(acc.ops)
+}
+
+fn patch_helper(
+    acc: &mut JsonPatch,
+    location: &Location,
+    prev: &Value,
+    current: &Value,
+) {
+    match (prev, current) {
+        (Value::Array(a), Value::Array(b)) => {
+            // Arrays with a key field are patched by position. Matching by key
+            // would need "move" operations to reproduce the new order.
+            if acc.options.array_key(&location.path).is_some() {
+                patch_array(acc, location, a, b);
+            } else if acc.options.array_as_set {
+                patch_array_set(acc, location, a, b);
+            } else {
+                patch_array(acc, location, a, b);
+            }
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            patch_obj(acc, location, a, b);
+        }
+        (a, b) => acc.replace(location, a, b),
+    }
+}
+
+fn patch_array(
+    acc: &mut JsonPatch,
+    location: &Location,
+    a: &[Value],
+    b: &[Value],
+) {
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+
+    // Replacing elements doesn't shift indices, so it goes first.
+    for (a_range, b_range) in array_gaps(a, b) {
+        let changed_range = changed_range(&a_range, &b_range);
+        for i in changed_range.clone() {
+            acc.replace(&location.index(i), &a[i], &b[i]);
+        }
+        removed.extend(a_range.filter(|i| !changed_range.contains(i)));
+        added.extend(b_range.filter(|i| !changed_range.contains(i)));
+    }
+
+    for i in removed.into_iter().rev() {
+        acc.push("remove", &location.index(i), None);
+    }
+    for i in added {
+        acc.push("add", &location.index(i), Some(&b[i]));
+    }
+}
+
+// Elements only in `b` are appended, so the patched array has the same
+// elements as `b` but may have them in a different order.
+fn patch_array_set(
+    acc: &mut JsonPatch,
+    location: &Location,
+    a: &[Value],
+    b: &[Value],
+) {
+    let (removed, added) = unmatched_elements(a, b);
+
+    for i in removed.into_iter().rev() {
+        acc.push("remove", &location.index(i), None);
+    }
+    for j in added {
+        acc.push("add", &location.end(j), Some(&b[j]));
+    }
+}
+
+fn patch_obj(
+    acc: &mut JsonPatch,
+    location: &Location,
+    a: &Map<String, Value>,
+    b: &Map<String, Value>,
+) {
+    for (k, a_v) in a {
+        let new_location = location.key(k);
+        match b.get(k) {
+            Some(b_v) => {
+                patch_helper(acc, &new_location, a_v, b_v);
+            }
+            None => {
+                acc.push("remove", &new_location, None);
+            }
+        }
+    }
+
+    for (k, b_v) in b {
+        if !a.contains_key(k) {
+            acc.push("add", &location.key(k), Some(b_v));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vec_value_string(strs: &[&str]) -> Vec<Value> {
+        let mut v: Vec<Value> = Vec::new();
+        for s in strs {
+            v.push(Value::String(s.to_string()));
+        }
+
+        v
+    }
+
+    #[test]
+    fn test_diff_array_1() {
+        let a = vec_value_string(&["foo", "bar"]);
+        let b = vec_value_string(&["foo", "bar", "baz"]);
+
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
+
+        assert_eq!(*acc, vec![Op::Added(".2".to_string(), Value::from("baz"))]);
+    }
+
+    #[test]
+    fn test_diff_array_2() {
+        let a = vec_value_string(&["foo", "bar", "baz"]);
+        let b = vec_value_string(&["foo", "bar"]);
+
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
+
+        assert_eq!(
+            *acc,
+            vec![Op::Removed(".2".to_string(), Value::from("baz"))]
+        );
     }
 
     #[test]
@@ -262,8 +1273,8 @@ mod tests {
         let a = vec_value_string(&["foo", "baz"]);
         let b = vec_value_string(&["foo", "bar", "baz"]);
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
 
         assert_eq!(
             *acc,
@@ -276,8 +1287,8 @@ mod tests {
         let a = vec_value_string(&["foo", "bar", "baz"]);
         let b = vec_value_string(&["foo", "baz"]);
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
 
         assert_eq!(
             *acc,
@@ -290,8 +1301,8 @@ mod tests {
         let a = vec_value_string(&["bar", "baz"]);
         let b = vec_value_string(&["foo", "bar", "baz"]);
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
 
         assert_eq!(
             *acc,
@@ -304,8 +1315,8 @@ mod tests {
         let a = vec_value_string(&["foo", "bar", "baz"]);
         let b = vec_value_string(&["bar", "baz"]);
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
 
         assert_eq!(
             *acc,
@@ -330,8 +1341,8 @@ mod tests {
             Value::from("d"),
         ];
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
 
         assert_eq!(
             *acc,
@@ -365,8 +1376,8 @@ mod tests {
         let a = vec_value_string(&["foo", "baz"]);
         let b = vec_value_string(&["bar"]);
 
-        let mut acc = JsonDiff(Vec::new());
-        diff_array(&mut acc, &"".to_string(), &a, &b);
+        let mut acc = JsonDiff::default();
+        diff_array(&mut acc, &Location::root(), &a, &b);
 
         assert_eq!(
             *acc,
@@ -380,4 +1391,679 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_to_json_1() {
+        let diff = JsonDiff::from_ops(vec![
+            Op::Added(".a".to_string(), Value::from(1)),
+            Op::Changed(".b".to_string(), Value::from(2), Value::from(3)),
+            Op::Removed(".c".to_string(), Value::from("foo")),
+        ]);
+
+        assert_eq!(
+            diff.to_json().to_string(),
+            concat!(
+                r#"[{"op":"added","path":".a","new":1},"#,
+                r#"{"op":"changed","path":".b","old":2,"new":3},"#,
+                r#"{"op":"removed","path":".c","old":"foo"}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_type_change_1() {
+        let diff = diff(
+            &Some(json!({"a": "x", "b": 1, "c": null, "d": [1]})),
+            &Some(json!({"a": {"y": 1}, "b": 2, "c": true, "d": 1})),
+        );
+
+        assert_eq!(
+            diff.to_string(),
+            concat!(
+                "~ .a: string -> object (\"x\" -> {\"y\":1})\n",
+                ".b: 1 -> 2\n",
+                ".c: null -> true\n",
+                "~ .d: array -> number ([1] -> 1)\n",
+            )
+        );
+        assert_eq!(
+            diff[0].to_json(),
+            json!({
+                "op": "changed",
+                "path": ".a",
+                "old": "x",
+                "new": {"y": 1},
+                "old_type": "string",
+                "new_type": "object",
+            })
+        );
+        assert_eq!(diff[1].type_change(), None);
+    }
+
+    #[test]
+    fn test_max_depth_1() {
+        let diff = JsonDiff::from_ops(vec![
+            Op::Added(".a".to_string(), json!({"b": {"c": 1}, "d": [1, 2]})),
+            Op::Changed(".e.f".to_string(), json!({"g": 1}), json!([])),
+            Op::Removed(".h".to_string(), json!({"i": [{}]})),
+        ]);
+
+        assert_eq!(
+            diff.to_text(Some(2)),
+            concat!(
+                "+ .a: {\"b\":{ ... 1 key },\"d\":[ ... 2 items ]}\n",
+                "~ .e.f: object -> array ({ ... 1 key } -> [])\n",
+                "- .h: {\"i\":[ ... 1 item ]}\n",
+            )
+        );
+        assert_eq!(diff.to_text(None), diff.to_string());
+    }
+
+    #[test]
+    fn test_truncate_strings_1() {
+        let value = json!({"a": "0123456789", "b": ["ééé", 5], "c": "012"});
+
+        assert_eq!(
+            truncate_strings(&value, 3),
+            json!({"a": "012...(10 bytes)", "b": ["ééé", 5], "c": "012"})
+        );
+        assert_eq!(
+            truncate_strings(&value, 2),
+            json!({"a": "01...(10 bytes)", "b": ["éé...(6 bytes)", 5], "c": "01...(3 bytes)"})
+        );
+
+        let diff = JsonDiff::from_ops(vec![Op::Changed(
+            ".a".to_string(),
+            json!("abcdef"),
+            json!("abcdeg"),
+        )]);
+        assert_eq!(
+            diff.truncated(4).to_string(),
+            ".a: \"abcd...(6 bytes)\" -> \"abcd...(6 bytes)\"\n"
+        );
+    }
+
+    #[test]
+    fn test_canonical_1() {
+        let value = json!({"b": [3, {"y": 1, "x": 2}, 1], "a": null});
+
+        assert_eq!(
+            canonical(&value, false).to_string(),
+            r#"{"a":null,"b":[3,{"x":2,"y":1},1]}"#
+        );
+        assert_eq!(
+            canonical(&value, true).to_string(),
+            r#"{"a":null,"b":[1,3,{"x":2,"y":1}]}"#
+        );
+    }
+
+    #[test]
+    fn test_unified_1() {
+        let prev = Some(json!({"b": 2, "c": [null]}));
+        let current = Some(json!({"a": 1, "b": "x", "c": []}));
+
+        assert_eq!(
+            diff(&prev, &current).unified(),
+            "@@ .b @@\n-2\n+\"x\"\n@@ .c.0 @@\n-null\n@@ .a @@\n+1\n"
+        );
+    }
+
+    #[test]
+    fn test_structured_1() {
+        let prev = Some(json!({"a": 1, "b.c": [1, 2], "d": {"e": true}}));
+        let current = Some(json!({"a": 2, "b.c": [1], "f/g": null}));
+        let diff = diff(&prev, &current).structured();
+
+        assert_eq!(
+            diff,
+            Diff {
+                added: vec![Change {
+                    path: ".f/g".to_string(),
+                    pointer: "/f~1g".to_string(),
+                    old: None,
+                    new: Some(Value::Null),
+                }],
+                removed: vec![
+                    Change {
+                        path: ".b.c.1".to_string(),
+                        pointer: "/b.c/1".to_string(),
+                        old: Some(json!(2)),
+                        new: None,
+                    },
+                    Change {
+                        path: ".d".to_string(),
+                        pointer: "/d".to_string(),
+                        old: Some(json!({"e": true})),
+                        new: None,
+                    },
+                ],
+                changed: vec![Change {
+                    path: ".a".to_string(),
+                    pointer: "/a".to_string(),
+                    old: Some(json!(1)),
+                    new: Some(json!(2)),
+                }],
+            }
+        );
+        assert_eq!(diff.len(), 4);
+    }
+
+    #[test]
+    fn test_structured_2() {
+        // The same dotted path for a key with a dot and for nested keys.
+        let prev = Some(json!({"a.b": 1, "a": {"b": 1}}));
+        let current = Some(json!({"a.b": 2, "a": {"b": 3}}));
+        let diff = diff(&prev, &current).structured();
+        let pointers = diff
+            .changed
+            .iter()
+            .map(|change| (change.path.as_str(), change.pointer.as_str()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(pointers, vec![(".a.b", "/a.b"), (".a.b", "/a/b")]);
+    }
+
+    #[test]
+    fn test_absent_document_1() {
+        let doc = Some(json!({"a": 1, "b": [2]}));
+
+        assert_eq!(
+            *diff(&doc, &None),
+            vec![Op::Removed("".to_string(), json!({"a": 1, "b": [2]}))]
+        );
+        assert_eq!(
+            *diff(&None, &doc),
+            vec![Op::Added("".to_string(), json!({"a": 1, "b": [2]}))]
+        );
+        assert!(diff(&None, &None).is_empty());
+    }
+
+    #[test]
+    fn test_sort_by_path_1() {
+        let mut diff = JsonDiff::from_ops(vec![
+            Op::Added(".b".to_string(), Value::from(1)),
+            Op::Changed(".a.10".to_string(), Value::from(2), Value::from(3)),
+            Op::Removed(".a.2".to_string(), Value::from(4)),
+            Op::Added(".a.x".to_string(), Value::from(5)),
+        ]);
+        diff.sort_by_path();
+
+        assert_eq!(
+            diff.iter().map(Op::path).collect::<Vec<_>>(),
+            vec![".a.2", ".a.10", ".a.x", ".b"]
+        );
+    }
+
+    #[test]
+    fn test_stats_1() {
+        let diff = JsonDiff::from_ops(vec![
+            Op::Added(".a".to_string(), Value::from(1)),
+            Op::Added(".b".to_string(), Value::from(2)),
+            Op::Removed(".c".to_string(), Value::from(3)),
+        ]);
+        let mut stats = diff.stats();
+
+        assert_eq!(
+            stats,
+            DiffStats {
+                added: 2,
+                changed: 0,
+                removed: 1
+            }
+        );
+
+        stats += stats;
+        assert_eq!(
+            stats,
+            DiffStats {
+                added: 4,
+                changed: 0,
+                removed: 2
+            }
+        );
+    }
+
+    fn patch_string(prev: &str, current: &str) -> String {
+        json_patch(
+            &Some(serde_json::from_str(prev).unwrap()),
+            &Some(serde_json::from_str(current).unwrap()),
+        )
+        .to_string()
+    }
+
+    #[test]
+    fn test_escape_pointer_token_1() {
+        assert_eq!(escape_pointer_token("a/b"), "a~1b");
+        assert_eq!(escape_pointer_token("m~n"), "m~0n");
+        assert_eq!(escape_pointer_token("~/"), "~0~1");
+        assert_eq!(escape_pointer_token("~1"), "~01");
+    }
+
+    #[test]
+    fn test_json_patch_1() {
+        assert_eq!(
+            patch_string(r#"{"a/b": 1, "m~n": 2}"#, r#"{"a/b": 3}"#),
+            concat!(
+                r#"[{"op":"replace","path":"/a~1b","value":3},"#,
+                r#"{"op":"remove","path":"/m~0n"}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_patch_2() {
+        assert_eq!(
+            patch_string(
+                r#"{"x": ["foo", "bar", "baz", "qux"]}"#,
+                r#"{"x": ["bar", "qux", "quux"]}"#
+            ),
+            concat!(
+                r#"[{"op":"remove","path":"/x/2"},"#,
+                r#"{"op":"remove","path":"/x/0"},"#,
+                r#"{"op":"add","path":"/x/2","value":"quux"}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_patch_3() {
+        assert_eq!(
+            patch_string(r#"{"a": 1}"#, r#"{"a": [1], "b": {"c": null}}"#),
+            concat!(
+                r#"[{"op":"replace","path":"/a","value":[1]},"#,
+                r#"{"op":"add","path":"/b","value":{"c":null}}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_json_patch_4() {
+        assert_eq!(json_patch(&None, &None).to_string(), "[]");
+        assert_eq!(
+            json_patch(&None, &Some(Value::from(1))).to_string(),
+            r#"[{"op":"add","path":"","value":1}]"#
+        );
+        assert_eq!(
+            json_patch(&Some(Value::from(1)), &None).to_string(),
+            r#"[{"op":"remove","path":""}]"#
+        );
+    }
+
+    fn pattern(s: &str) -> PathPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_path_pattern_1() {
+        let p = pattern("meta.timestamp");
+        assert!(p.matches(".meta.timestamp"));
+        assert!(!p.matches(".meta.timestamp.foo"));
+        assert!(!p.matches(".meta.timestamps"));
+        assert!(!p.matches(".meta"));
+        assert_eq!(p, pattern(".meta.timestamp"));
+    }
+
+    #[test]
+    fn test_path_pattern_2() {
+        let p = pattern("meta.*");
+        assert!(p.matches(".meta"));
+        assert!(p.matches(".meta.timestamp"));
+        assert!(p.matches(".meta.a.b"));
+        assert!(!p.matches(".metadata"));
+        assert!(!p.matches(".foo.meta"));
+    }
+
+    #[test]
+    fn test_path_pattern_3() {
+        assert!(pattern("*").matches(""));
+        assert!(pattern("*").matches(".foo"));
+        assert!("a.*.b".parse::<PathPattern>().is_err());
+        assert!("a*".parse::<PathPattern>().is_err());
+    }
+
+    #[test]
+    fn test_diff_with_ignore_1() {
+        let prev = serde_json::json!({
+            "meta": {"timestamp": 1, "request_id": "a"},
+            "value": 1
+        });
+        let current = serde_json::json!({
+            "meta": {"timestamp": 2, "request_id": "b"},
+            "value": 2
+        });
+        let options = DiffOptions {
+            ignore: vec![pattern("meta.timestamp")],
+            ..Default::default()
+        };
+
+        let diff = diff_with(&Some(prev), &Some(current), &options);
+
+        assert_eq!(
+            *diff,
+            vec![
+                Op::Changed(
+                    ".meta.request_id".to_string(),
+                    Value::from("a"),
+                    Value::from("b")
+                ),
+                Op::Changed(
+                    ".value".to_string(),
+                    Value::from(1),
+                    Value::from(2)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_with_ignore_values_1() {
+        let prev = serde_json::json!({
+            "token": "0f8fad5b-d9cb-469f-a165-70867728950e",
+            "n": 1,
+            "name": "a"
+        });
+        let current = serde_json::json!({
+            "token": "7c9e6679-7425-40de-944b-e07fc1f90ae7",
+            "n": 2,
+            "name": "0f8fad5b-d9cb-469f-a165-70867728950e"
+        });
+        let options = DiffOptions {
+            ignore_values: vec![
+                Regex::new("^[0-9a-f]{8}(-[0-9a-f]{4}){3}-[0-9a-f]{12}$")
+                    .unwrap(),
+                Regex::new("^[0-9]$").unwrap(),
+            ],
+            ..Default::default()
+        };
+
+        let diff = diff_with(&Some(prev), &Some(current), &options);
+
+        assert_eq!(
+            *diff,
+            vec![Op::Changed(
+                ".name".to_string(),
+                Value::from("a"),
+                Value::from("0f8fad5b-d9cb-469f-a165-70867728950e")
+            )]
+        );
+    }
+
+    #[test]
+    fn test_diff_with_ignore_2() {
+        let prev = serde_json::json!({"meta": {"a": 1}, "value": 1});
+        let current = serde_json::json!({"meta": {"b": [2]}, "value": 1});
+        let options = DiffOptions {
+            ignore: vec![pattern("meta.*")],
+            ..Default::default()
+        };
+
+        let diff = diff_with(&Some(prev), &Some(current), &options);
+
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn test_json_patch_with_ignore_1() {
+        let prev = serde_json::json!({"meta": {"a": 1}, "value": 1});
+        let current = serde_json::json!({"meta": {"b": 2}, "value": 2});
+        let options = DiffOptions {
+            ignore: vec![pattern("meta.*")],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            json_patch_with(&Some(prev), &Some(current), &options).to_string(),
+            r#"[{"op":"replace","path":"/value","value":2}]"#
+        );
+    }
+
+    #[test]
+    fn test_json_patch_with_ignore_values_1() {
+        let prev = serde_json::json!({"id": "a1", "ids": ["b2"], "n": 1});
+        let current = serde_json::json!({"id": "c3", "ids": ["d4"], "n": 20});
+        let options = DiffOptions {
+            ignore_values: vec![Regex::new("^[a-z][0-9]$").unwrap()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            json_patch_with(&Some(prev), &Some(current), &options).to_string(),
+            r#"[{"op":"replace","path":"/n","value":20}]"#
+        );
+    }
+
+    #[test]
+    fn test_path_pattern_4() {
+        let p = pattern("a.b");
+        assert!(p.in_subtree(".a.b"));
+        assert!(p.in_subtree(".a.b.c"));
+        assert!(!p.in_subtree(".a"));
+        assert!(p.is_below(""));
+        assert!(p.is_below(".a"));
+        assert!(!p.is_below(".a.b"));
+        assert!(!p.is_below(".ab"));
+    }
+
+    #[test]
+    fn test_path_pattern_5() {
+        assert_eq!(pattern("meta.timestamp").to_string(), ".meta.timestamp");
+        assert_eq!(pattern(".meta.*").to_string(), ".meta.*");
+        assert_eq!(pattern("*").to_string(), "*");
+    }
+
+    #[test]
+    fn test_touches_1() {
+        let prev = Some(serde_json::json!({
+            "status": {"phase": "Pending", "ready": 0},
+            "spec": {"replicas": 1}
+        }));
+        let current = Some(serde_json::json!({
+            "status": {"phase": "Pending", "ready": 1},
+            "spec": {"replicas": 2}
+        }));
+        let d = diff(&prev, &current);
+        assert!(!d.touches(&[pattern("status.phase")]));
+        assert!(d.touches(&[pattern("status.phase"), pattern("spec")]));
+        assert!(d.touches(&[pattern("status.*")]));
+
+        let current = Some(serde_json::json!({"status": "gone"}));
+        assert!(diff(&prev, &current).touches(&[pattern("status.phase")]));
+        assert!(diff(&prev, &None).touches(&[pattern("status.phase")]));
+    }
+
+    #[test]
+    fn test_select_1() {
+        let value = serde_json::json!({
+            "status": {"health": "ok", "replicas": 3, "other": true},
+            "spec": {"foo": 1}
+        });
+        let patterns =
+            vec![pattern("status.health"), pattern("status.replicas")];
+
+        assert_eq!(
+            select(&value, &patterns),
+            serde_json::json!({"status": {"health": "ok", "replicas": 3}})
+        );
+    }
+
+    #[test]
+    fn test_select_2() {
+        let value = serde_json::json!({"items": [1, {"a": 1, "b": 2}, 3, 4]});
+        let patterns = vec![pattern("items.1.b"), pattern("missing.*")];
+
+        assert_eq!(
+            select(&value, &patterns),
+            serde_json::json!({"items": [null, {"b": 2}]})
+        );
+    }
+
+    #[test]
+    fn test_select_3() {
+        let value = serde_json::json!({"a": 1});
+
+        assert_eq!(select(&value, &[pattern("*")]), value);
+        assert_eq!(select(&value, &[pattern("b")]), serde_json::json!({}));
+        assert_eq!(select(&Value::from(1), &[pattern("b")]), Value::Null);
+    }
+
+    #[test]
+    fn test_diff_with_epsilon_1() {
+        let prev =
+            serde_json::json!({"a": 0.3, "b": 1, "c": [1.0, 5], "d": "0.3"});
+        let current = serde_json::json!({"a": 0.30000001, "b": 2, "c": [1.001, 5], "d": "0.30000001"});
+        let options = DiffOptions {
+            epsilon: Some(0.01),
+            ..Default::default()
+        };
+
+        let diff =
+            diff_with(&Some(prev.clone()), &Some(current.clone()), &options);
+
+        assert_eq!(
+            *diff,
+            vec![
+                Op::Changed(".b".to_string(), Value::from(1), Value::from(2)),
+                Op::Changed(
+                    ".d".to_string(),
+                    Value::from("0.3"),
+                    Value::from("0.30000001")
+                ),
+            ]
+        );
+        assert_eq!(
+            json_patch_with(&Some(prev), &Some(current), &options).to_string(),
+            concat!(
+                r#"[{"op":"replace","path":"/b","value":2},"#,
+                r#"{"op":"replace","path":"/d","value":"0.30000001"}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_diff_with_epsilon_rel_1() {
+        let prev = serde_json::json!([1000.0, 1.0, 0]);
+        let current = serde_json::json!([1001.0, 1.1, 0.0001]);
+        let options = DiffOptions {
+            epsilon_rel: Some(0.01),
+            ..Default::default()
+        };
+
+        let diff = diff_with(&Some(prev), &Some(current), &options);
+
+        assert_eq!(
+            *diff,
+            vec![
+                Op::Changed(
+                    ".1".to_string(),
+                    Value::from(1.0),
+                    Value::from(1.1)
+                ),
+                Op::Changed(
+                    ".2".to_string(),
+                    Value::from(0),
+                    Value::from(0.0001)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_with_array_as_set_1() {
+        let prev = serde_json::json!({"nodes": [1, 2, 3, 3]});
+        let current = serde_json::json!({"nodes": [3, 4, 1, 3]});
+        let options = DiffOptions {
+            array_as_set: true,
+            ..Default::default()
+        };
+
+        let diff = diff_with(&Some(prev), &Some(current), &options);
+
+        assert_eq!(
+            *diff,
+            vec![
+                Op::Removed(".nodes.1".to_string(), Value::from(2)),
+                Op::Added(".nodes.1".to_string(), Value::from(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_with_array_as_set_2() {
+        let prev = serde_json::json!([{"id": 1}, {"id": 2}]);
+        let current = serde_json::json!([{"id": 2}, {"id": 1}]);
+        let options = DiffOptions {
+            array_as_set: true,
+            ..Default::default()
+        };
+
+        assert!(diff_with(&Some(prev), &Some(current), &options).is_empty());
+    }
+
+    #[test]
+    fn test_json_patch_with_array_as_set_1() {
+        let prev = serde_json::json!([1, 2, 3]);
+        let current = serde_json::json!([4, 3, 1]);
+        let options = DiffOptions {
+            array_as_set: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            json_patch_with(&Some(prev), &Some(current), &options).to_string(),
+            concat!(
+                r#"[{"op":"remove","path":"/1"},"#,
+                r#"{"op":"add","path":"/-","value":4}]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_array_key_1() {
+        let key: ArrayKey = "users=id".parse().unwrap();
+        assert_eq!(key.path, pattern("users"));
+        assert_eq!(key.field, "id");
+
+        assert!("users".parse::<ArrayKey>().is_err());
+        assert!("users=".parse::<ArrayKey>().is_err());
+        assert!("us*ers=id".parse::<ArrayKey>().is_err());
+    }
+
+    #[test]
+    fn test_diff_with_array_key_1() {
+        let prev = serde_json::json!({"users": [
+            {"id": 1, "name": "a"},
+            {"id": 2, "name": "b"},
+            {"id": 3, "name": "c"}
+        ]});
+        let current = serde_json::json!({"users": [
+            {"id": 0, "name": "z"},
+            {"id": 1, "name": "a"},
+            {"id": 3, "name": "C"}
+        ]});
+        let options = DiffOptions {
+            array_keys: vec!["users=id".parse().unwrap()],
+            ..Default::default()
+        };
+
+        let diff = diff_with(&Some(prev), &Some(current), &options);
+
+        assert_eq!(
+            *diff,
+            vec![
+                Op::Changed(
+                    ".users.2.name".to_string(),
+                    Value::from("c"),
+                    Value::from("C")
+                ),
+                Op::Removed(
+                    ".users.1".to_string(),
+                    serde_json::json!({"id": 2, "name": "b"})
+                ),
+                Op::Added(
+                    ".users.0".to_string(),
+                    serde_json::json!({"id": 0, "name": "z"})
+                ),
+            ]
+        );
+    }
 }
@@ -21,7 +21,7 @@ impl DerefMut for Lengths {
 }
 
 impl Lengths {
-    pub fn new<T>(a: &Vec<T>, b: &Vec<T>) -> Lengths
+    pub fn new<T>(a: &[T], b: &[T]) -> Lengths
     where
         T: PartialEq,
     {
@@ -29,9 +29,7 @@ impl Lengths {
         let h = b.len() + 1;
 
         // v[i, j] => v[w * j + i]
-        let mut v = Vec::new();
-        v.resize(w * h, 0);
-        let mut m = Lengths(v);
+        let mut m = Lengths(vec![0; w * h]);
 
         for j in 1..h {
             for i in 1..w {
@@ -54,8 +52,8 @@ impl Lengths {
 
     pub fn backtrack<T>(
         &self,
-        a: &Vec<T>,
-        b: &Vec<T>,
+        a: &[T],
+        b: &[T],
         i: usize,
         j: usize,
     ) -> Vec<(usize, usize)>
@@ -81,7 +79,7 @@ impl Lengths {
     }
 }
 
-pub fn lcs<T>(a: &Vec<T>, b: &Vec<T>) -> Vec<(usize, usize)>
+pub fn lcs<T>(a: &[T], b: &[T]) -> Vec<(usize, usize)>
 where
     T: PartialEq,
 {
@@ -89,7 +87,7 @@ where
     m.backtrack(a, b, a.len(), b.len())
 }
 
-pub fn pick<T>(a: &Vec<T>, idx: Vec<(usize, usize)>) -> Vec<T>
+pub fn pick<T>(a: &[T], idx: Vec<(usize, usize)>) -> Vec<T>
 where
     T: Clone,
 {
@@ -107,13 +105,13 @@ mod tests {
 
     #[test]
     fn test_lengths_1() {
-        let m = Lengths::new(&vec![1, 2], &vec![1, 2]);
+        let m = Lengths::new(&[1, 2], &[1, 2]);
         assert_eq!(m.0, vec![0, 0, 0, 0, 1, 1, 0, 1, 2]);
     }
 
     #[test]
     fn test_lengths_2() {
-        let m = Lengths::new(&vec![1, 3, 5], &vec![1, 2, 3, 4, 5]);
+        let m = Lengths::new(&[1, 3, 5], &[1, 2, 3, 4, 5]);
         assert_eq!(
             m.0,
             vec![
@@ -129,7 +127,7 @@ mod tests {
 
     #[test]
     fn test_lengths_3() {
-        let m = Lengths::new(&vec![1, 2, 3, 4, 5], &vec![1, 3, 5]);
+        let m = Lengths::new(&[1, 2, 3, 4, 5], &[1, 3, 5]);
         assert_eq!(
             m.0,
             vec![
@@ -143,8 +141,8 @@ mod tests {
 
     #[test]
     fn test_backtrack_1() {
-        let a = &vec![1, 3, 5, 7];
-        let b = &vec![5, 3, 7];
+        let a = &[1, 3, 5, 7];
+        let b = &[5, 3, 7];
         let m = Lengths::new(a, b);
         let idx = m.backtrack(a, b, a.len(), b.len());
         assert_eq!(idx, vec![(1, 1), (3, 2)]);
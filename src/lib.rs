@@ -1,2 +1,3 @@
 pub mod diff;
 pub mod lcs;
+pub mod watcher;
@@ -0,0 +1,162 @@
+// Messages on stderr. Every message goes through this module, which decides
+// whether to print it and how.
+
+use crate::{current_timestamp, escape_for_terminal, timestamp_value};
+use clap::ValueEnum;
+use std::{cell::Cell, sync::OnceLock};
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Level {
+    Error,
+    Warning,
+    Info,
+    Heartbeat,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+            Level::Heartbeat => "heartbeat",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+// Set once in `main()` from '--log-format'.
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+thread_local! {
+    // Set for each source from '-v' and '--quiet', since every source
+    // runs on a thread of its own.
+    static VERBOSITY: Cell<(u8, bool)> = const { Cell::new((0, false)) };
+}
+
+pub fn init(format: LogFormat) {
+    FORMAT.set(format).unwrap();
+}
+
+pub fn set_verbosity(verbose: u8, quiet: bool) {
+    VERBOSITY.with(|verbosity| verbosity.set((verbose, quiet)));
+}
+
+fn verbose() -> u8 {
+    VERBOSITY.with(|verbosity| verbosity.get().0)
+}
+
+fn quiet() -> bool {
+    VERBOSITY.with(|verbosity| verbosity.get().1)
+}
+
+fn format() -> LogFormat {
+    FORMAT.get().copied().unwrap_or(LogFormat::Text)
+}
+
+// Print a message like "[ERROR <timestamp>] message" or as a JSON object on
+// a line of its own.
+fn log(level: Level, message: &str) {
+    let timestamp = current_timestamp();
+
+    match format() {
+        LogFormat::Text => eprintln!(
+            "[{} {}] {}",
+            level.name().to_uppercase(),
+            timestamp,
+            message
+        ),
+        LogFormat::Json => eprintln!(
+            "{}",
+            serde_json::json!({
+                "level": level.name(),
+                "timestamp": timestamp_value(&timestamp),
+                "message": message,
+            })
+        ),
+    }
+}
+
+// An error jsonwatch recovers from, like a failed poll. Printed with '-v'.
+pub fn error(message: &str) {
+    if verbose() >= 1 {
+        log(Level::Error, message);
+    }
+}
+
+// An error that stops jsonwatch or fails '--check'. Always printed.
+pub fn fatal(message: &str) {
+    log(Level::Error, message);
+}
+
+// Printed unless '--quiet'.
+pub fn warn(message: &str) {
+    if !quiet() {
+        log(Level::Warning, message);
+    }
+}
+
+// A warning about a single poll, like an error jsonwatch recovers from.
+// Printed with '-v'.
+pub fn poll_warn(message: &str) {
+    if verbose() >= 1 {
+        log(Level::Warning, message);
+    }
+}
+
+// Printed unless '--quiet'.
+pub fn info(message: &str) {
+    if !quiet() {
+        log(Level::Info, message);
+    }
+}
+
+// Asked for with '--heartbeat', so always printed.
+pub fn heartbeat(message: &str) {
+    log(Level::Heartbeat, message);
+}
+
+// Printed with '-vv'.
+pub fn debug(message: &str) {
+    if verbose() >= 2 {
+        log(Level::Debug, message);
+    }
+}
+
+// The data of a poll, printed with '-vv'.
+pub fn debug_data(data: &str) {
+    if verbose() < 2 {
+        return;
+    }
+
+    // A JSON string holds any data on one line.
+    if format() == LogFormat::Json {
+        log(Level::Debug, &format!("Input data: {}", data));
+        return;
+    }
+
+    let timestamp = current_timestamp();
+
+    let multiline = data.trim_end().contains('\n') || data.ends_with("\n\n");
+    let escaped = escape_for_terminal(data);
+
+    if multiline {
+        eprint!("[DEBUG {}] Multiline input data:\n{}", timestamp, escaped);
+    } else {
+        eprint!("[DEBUG {}] Input data: {}", timestamp, escaped);
+    }
+
+    if !data.is_empty() && !data.ends_with('\n') {
+        eprintln!();
+    }
+    if multiline {
+        eprintln!("[DEBUG {}] End of multiline input data", timestamp);
+    }
+}
@@ -0,0 +1,617 @@
+use crate::diff::{self, DiffOptions, JsonDiff};
+use serde_json::Value;
+use std::{
+    error::Error,
+    mem, thread,
+    time::{Duration, Instant},
+};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const ADAPTIVE_GROWTH: f64 = 1.5;
+
+/// What the watcher does after a callback returns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flow {
+    /// Go on polling.
+    Continue,
+    /// Stop polling and return from `Watcher::run`.
+    Stop,
+    /// Forget the data, so the next document is a new first value.
+    Restart,
+}
+
+/// Counts of what the watcher has seen so far.
+#[derive(Clone, Copy, Debug)]
+pub struct Status {
+    /// Polls so far, including failed ones.
+    pub polls: u64,
+    /// Changes reported so far.
+    pub changes: u32,
+    /// Successful polls where the data was the same as before.
+    pub unchanged: u64,
+    /// Failed polls in a row.
+    pub errors: u32,
+    /// When the last change was reported or the watcher started.
+    pub last_change: Instant,
+}
+
+type Fetch<'a> =
+    Box<dyn FnMut(&Status) -> Result<Option<Value>, Box<dyn Error>> + 'a>;
+type Wait<'a> = Box<dyn FnMut(&Status, Option<Duration>) -> Flow + 'a>;
+type Filter<'a> = Box<dyn FnMut(&JsonDiff) -> bool + 'a>;
+type OnInitial<'a> = Box<dyn FnMut(&Status, &Value) + 'a>;
+type OnPoll<'a> = Box<dyn FnMut(&Status, &Option<Value>) + 'a>;
+type OnDiff<'a> = Box<
+    dyn FnMut(&Status, &Option<Value>, &Option<Value>, JsonDiff) -> Flow + 'a,
+>;
+type OnError<'a> = Box<dyn FnMut(&Status, Box<dyn Error>) -> Flow + 'a>;
+
+/// Polls a source of JSON data and calls back when it changes.
+///
+/// The source returns `None` when there is no document, like for an empty
+/// response. The first document is the one later documents are compared
+/// with. Failed polls are passed to the error callback and don't change the
+/// data.
+///
+/// ```no_run
+/// use jsonwatch::watcher::{Flow, Watcher};
+/// use std::time::Duration;
+///
+/// let mut watcher = Watcher::builder(|_| Ok(Some(serde_json::json!(1))))
+///     .interval(Duration::from_secs(5))
+///     .changes(1)
+///     .on_diff(|_, _prev, _current, diff| {
+///         print!("{}", diff);
+///         Flow::Continue
+///     })
+///     .build();
+/// watcher.run();
+/// ```
+pub struct Watcher<'a> {
+    fetch: Fetch<'a>,
+    interval: Duration,
+    changes: Option<u32>,
+    diff_options: DiffOptions,
+    first: Option<Result<Option<Value>, Box<dyn Error>>>,
+    poll_right_away: bool,
+    baseline_fixed: bool,
+    report_absence: bool,
+    debounce: Option<Duration>,
+    backoff_max: Option<Duration>,
+    adaptive: Option<(Duration, Duration)>,
+    wait: Option<Wait<'a>>,
+    filter: Option<Filter<'a>>,
+    on_initial: Option<OnInitial<'a>>,
+    on_poll: Option<OnPoll<'a>>,
+    on_diff: Option<OnDiff<'a>>,
+    on_error: Option<OnError<'a>>,
+    data: Option<Value>,
+}
+
+/// Configures a `Watcher`.
+pub struct WatcherBuilder<'a> {
+    watcher: Watcher<'a>,
+}
+
+impl<'a> Watcher<'a> {
+    /// Start building a watcher that gets each document from `fetch`.
+    pub fn builder(
+        fetch: impl FnMut(&Status) -> Result<Option<Value>, Box<dyn Error>> + 'a,
+    ) -> WatcherBuilder<'a> {
+        WatcherBuilder {
+            watcher: Watcher {
+                fetch: Box::new(fetch),
+                interval: Duration::from_secs(1),
+                changes: None,
+                diff_options: DiffOptions::default(),
+                first: None,
+                poll_right_away: false,
+                baseline_fixed: false,
+                report_absence: false,
+                debounce: None,
+                backoff_max: None,
+                adaptive: None,
+                wait: None,
+                filter: None,
+                on_initial: None,
+                on_poll: None,
+                on_diff: None,
+                on_error: None,
+                data: None,
+            },
+        }
+    }
+
+    /// The latest document.
+    pub fn data(&self) -> Option<&Value> {
+        self.data.as_ref()
+    }
+
+    fn wait(&mut self, status: &Status, delay: Option<Duration>) -> Flow {
+        match &mut self.wait {
+            Some(wait) => wait(status, delay),
+            None => {
+                thread::sleep(delay.unwrap_or_default());
+                Flow::Continue
+            }
+        }
+    }
+
+    fn error(&mut self, status: &Status, e: Box<dyn Error>) -> Flow {
+        match &mut self.on_error {
+            Some(on_error) => on_error(status, e),
+            None => Flow::Continue,
+        }
+    }
+
+    fn initial(&mut self, status: &Status) {
+        if let (Some(on_initial), Some(json)) =
+            (&mut self.on_initial, &self.data)
+        {
+            on_initial(status, json);
+        }
+    }
+
+    /// Poll until the change limit is reached or a callback stops the
+    /// watcher, then return the counts. Without either, this doesn't return.
+    pub fn run(&mut self) -> Status {
+        let mut status = Status {
+            polls: 0,
+            changes: 0,
+            unchanged: 0,
+            errors: 0,
+            last_change: Instant::now(),
+        };
+        let mut interval = self.interval;
+        let mut delay = interval;
+        // A new document and when it first appeared for the debounce window.
+        let mut pending: Option<(Option<Value>, Instant)> = None;
+        let mut restart = false;
+
+        let first = match self.first.take() {
+            Some(first) => first,
+            None => {
+                status.polls += 1;
+                (self.fetch)(&status)
+            }
+        };
+        match first {
+            Ok(json) => {
+                if status.polls > 0 {
+                    if let Some(on_poll) = &mut self.on_poll {
+                        on_poll(&status, &json);
+                    }
+                }
+                self.data = json;
+            }
+            Err(e) => {
+                status.errors += 1;
+                match self.error(&status, e) {
+                    Flow::Continue => {}
+                    Flow::Stop => return status,
+                    Flow::Restart => restart = true,
+                }
+                self.data = None;
+            }
+        }
+        self.initial(&status);
+
+        // With `baseline_fixed`, changes are reported against the first value.
+        let mut fixed = self.baseline_fixed.then(|| self.data.clone());
+        let mut poll_now = self.poll_right_away;
+
+        loop {
+            if self.changes.is_some_and(|max| status.changes >= max) {
+                break;
+            }
+
+            let wait = (!poll_now).then_some(delay);
+            poll_now = false;
+            match self.wait(&status, wait) {
+                Flow::Continue => {}
+                Flow::Stop => break,
+                Flow::Restart => {
+                    self.data = None;
+                    pending = None;
+                    restart = true;
+                }
+            }
+
+            status.polls += 1;
+            let next = match (self.fetch)(&status) {
+                Ok(json) => {
+                    delay = interval;
+
+                    json
+                }
+                Err(e) => {
+                    status.errors += 1;
+                    match self.error(&status, e) {
+                        Flow::Continue => {}
+                        Flow::Stop => break,
+                        Flow::Restart => {
+                            self.data = None;
+                            pending = None;
+                            restart = true;
+                        }
+                    }
+
+                    if let Some(max) = self.backoff_max {
+                        delay = (delay * 2).max(MIN_BACKOFF).min(max);
+                    }
+
+                    continue;
+                }
+            };
+            status.errors = 0;
+            if let Some(on_poll) = &mut self.on_poll {
+                on_poll(&status, &next);
+            }
+
+            // Start over as if this were the first poll.
+            if restart {
+                restart = false;
+                self.data = next;
+                self.initial(&status);
+                if fixed.is_some() {
+                    fixed = Some(self.data.clone());
+                }
+
+                continue;
+            }
+
+            // Without `report_absence`, a poll without a document keeps the
+            // last one, and a document that appears is like the first value.
+            if !self.report_absence {
+                match (&self.data, &next) {
+                    (_, None) => continue,
+                    (None, Some(_)) => {
+                        self.data = next;
+                        self.initial(&status);
+                        if fixed.is_some() {
+                            fixed = Some(self.data.clone());
+                        }
+
+                        continue;
+                    }
+                    (Some(_), Some(_)) => {}
+                }
+            }
+
+            // Hold back a new value until it has lasted for the debounce
+            // window. Values that flap back to the reported one then cause
+            // no change.
+            if let Some(window) = self.debounce {
+                let since = match &pending {
+                    Some((value, since))
+                        if diff::diff_with(
+                            value,
+                            &next,
+                            &self.diff_options,
+                        )
+                        .is_empty() =>
+                    {
+                        *since
+                    }
+                    _ => Instant::now(),
+                };
+                pending = Some((next.clone(), since));
+
+                if since.elapsed() < window {
+                    continue;
+                }
+            }
+
+            let prev = mem::replace(&mut self.data, next);
+            let diff = diff::diff_with(&prev, &self.data, &self.diff_options);
+
+            if let Some((min, max)) = self.adaptive {
+                interval = if diff.is_empty() {
+                    interval.mul_f64(ADAPTIVE_GROWTH).min(max)
+                } else {
+                    (interval / 2).max(min)
+                };
+                delay = interval;
+            }
+
+            if diff.is_empty() {
+                status.unchanged += 1;
+                continue;
+            }
+
+            let (prev, diff) = match &fixed {
+                Some(first) => (
+                    first.clone(),
+                    diff::diff_with(first, &self.data, &self.diff_options),
+                ),
+                None => (prev, diff),
+            };
+            // The data changed back to the baseline.
+            if diff.is_empty() {
+                continue;
+            }
+            if let Some(filter) = &mut self.filter {
+                if !filter(&diff) {
+                    continue;
+                }
+            }
+
+            status.changes += 1;
+            let flow = match &mut self.on_diff {
+                Some(on_diff) => on_diff(&status, &prev, &self.data, diff),
+                None => Flow::Continue,
+            };
+            status.last_change = Instant::now();
+            match flow {
+                Flow::Continue => {}
+                Flow::Stop => break,
+                Flow::Restart => {
+                    self.data = None;
+                    pending = None;
+                    restart = true;
+                }
+            }
+        }
+
+        status
+    }
+}
+
+impl<'a> WatcherBuilder<'a> {
+    /// How long to wait between polls. The default is one second.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.watcher.interval = interval;
+        self
+    }
+
+    /// Stop after this many changes. With zero, stop after the first value.
+    pub fn changes(mut self, changes: u32) -> Self {
+        self.watcher.changes = Some(changes);
+        self
+    }
+
+    /// Paths to ignore, array keys, and other options for comparing values.
+    pub fn diff_options(mut self, options: DiffOptions) -> Self {
+        self.watcher.diff_options = options;
+        self
+    }
+
+    /// Start with this result instead of polling for the first value. An
+    /// error counts as a failed poll.
+    pub fn first(
+        mut self,
+        first: Result<Option<Value>, Box<dyn Error>>,
+    ) -> Self {
+        self.watcher.first = Some(first);
+        self
+    }
+
+    /// Poll right after the first value instead of waiting for the interval.
+    pub fn poll_right_away(mut self) -> Self {
+        self.watcher.poll_right_away = true;
+        self
+    }
+
+    /// Compare every document with the first value instead of the previous
+    /// one.
+    pub fn baseline_fixed(mut self) -> Self {
+        self.watcher.baseline_fixed = true;
+        self
+    }
+
+    /// Report a document that disappears or appears as a change. Otherwise,
+    /// a poll without a document keeps the last one.
+    pub fn report_absence(mut self) -> Self {
+        self.watcher.report_absence = true;
+        self
+    }
+
+    /// Only report a change once the data has stayed the same this long.
+    pub fn debounce(mut self, window: Duration) -> Self {
+        self.watcher.debounce = Some(window);
+        self
+    }
+
+    /// Double the interval after each failed poll up to `max`.
+    pub fn backoff(mut self, max: Duration) -> Self {
+        self.watcher.backoff_max = Some(max);
+        self
+    }
+
+    /// Halve the interval after each change and grow it by half after each
+    /// poll without one, keeping it between `min` and `max`.
+    pub fn adaptive(mut self, min: Duration, max: Duration) -> Self {
+        self.watcher.adaptive = Some((min, max));
+        self
+    }
+
+    /// Call `f` before each poll with the delay to wait for, or `None` to
+    /// poll right away, instead of sleeping.
+    pub fn wait(
+        mut self,
+        f: impl FnMut(&Status, Option<Duration>) -> Flow + 'a,
+    ) -> Self {
+        self.watcher.wait = Some(Box::new(f));
+        self
+    }
+
+    /// Only report the diffs for which `f` returns `true`.
+    pub fn filter(mut self, f: impl FnMut(&JsonDiff) -> bool + 'a) -> Self {
+        self.watcher.filter = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with each first value: at the start, after a restart, and
+    /// when a document appears without `report_absence`.
+    pub fn on_initial(mut self, f: impl FnMut(&Status, &Value) + 'a) -> Self {
+        self.watcher.on_initial = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the document of each successful poll.
+    pub fn on_poll(
+        mut self,
+        f: impl FnMut(&Status, &Option<Value>) + 'a,
+    ) -> Self {
+        self.watcher.on_poll = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the previous value, the current value, and the diff
+    /// between them on each change.
+    pub fn on_diff(
+        mut self,
+        f: impl FnMut(&Status, &Option<Value>, &Option<Value>, JsonDiff) -> Flow
+            + 'a,
+    ) -> Self {
+        self.watcher.on_diff = Some(Box::new(f));
+        self
+    }
+
+    /// Call `f` with the error when a poll fails.
+    pub fn on_error(
+        mut self,
+        f: impl FnMut(&Status, Box<dyn Error>) -> Flow + 'a,
+    ) -> Self {
+        self.watcher.on_error = Some(Box::new(f));
+        self
+    }
+
+    pub fn build(self) -> Watcher<'a> {
+        self.watcher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::cell::RefCell;
+
+    // A source that returns the documents in order and then stops the
+    // watcher with an error.
+    fn source(
+        documents: Vec<Option<Value>>,
+    ) -> impl FnMut(&Status) -> Result<Option<Value>, Box<dyn Error>> {
+        let mut documents = documents.into_iter();
+
+        move |_| documents.next().ok_or_else(|| "no more documents".into())
+    }
+
+    fn stop_on_error(_: &Status, _: Box<dyn Error>) -> Flow {
+        Flow::Stop
+    }
+
+    #[test]
+    fn test_watcher_1() {
+        let documents = vec![json!(1), json!(1), json!(2), json!(2), json!(3)];
+        let seen = RefCell::new(Vec::new());
+
+        let mut watcher =
+            Watcher::builder(source(documents.into_iter().map(Some).collect()))
+                .interval(Duration::ZERO)
+                .changes(2)
+                .on_diff(|_, prev, current, _| {
+                    seen.borrow_mut().push((prev.clone(), current.clone()));
+                    Flow::Continue
+                })
+                .on_error(|_, _| panic!("unexpected error"))
+                .build();
+
+        let status = watcher.run();
+        assert_eq!(status.changes, 2);
+        assert_eq!(status.unchanged, 2);
+        assert_eq!(watcher.data(), Some(&json!(3)));
+        drop(watcher);
+        assert_eq!(
+            seen.into_inner(),
+            vec![
+                (Some(json!(1)), Some(json!(2))),
+                (Some(json!(2)), Some(json!(3))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watcher_2() {
+        let documents = vec![None, Some(json!(1)), None, Some(json!(2))];
+        let initial = RefCell::new(Vec::new());
+        let seen = RefCell::new(Vec::new());
+
+        let mut watcher = Watcher::builder(source(documents.clone()))
+            .interval(Duration::ZERO)
+            .on_initial(|_, json| initial.borrow_mut().push(json.clone()))
+            .on_diff(|_, prev, current, _| {
+                seen.borrow_mut().push((prev.clone(), current.clone()));
+                Flow::Continue
+            })
+            .on_error(stop_on_error)
+            .build();
+        watcher.run();
+        drop(watcher);
+
+        // The document that appears is like a first value, and a missing
+        // one keeps the last.
+        assert_eq!(*initial.borrow(), vec![json!(1)]);
+        assert_eq!(*seen.borrow(), vec![(Some(json!(1)), Some(json!(2)))]);
+
+        seen.borrow_mut().clear();
+        let mut watcher = Watcher::builder(source(documents))
+            .interval(Duration::ZERO)
+            .report_absence()
+            .on_diff(|_, prev, current, _| {
+                seen.borrow_mut().push((prev.clone(), current.clone()));
+                Flow::Continue
+            })
+            .on_error(stop_on_error)
+            .build();
+        watcher.run();
+        drop(watcher);
+
+        assert_eq!(
+            seen.into_inner(),
+            vec![
+                (None, Some(json!(1))),
+                (Some(json!(1)), None),
+                (None, Some(json!(2))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_watcher_3() {
+        let documents = vec![json!(1), json!(2), json!(3), json!(4)];
+        let initial = RefCell::new(Vec::new());
+        let seen = RefCell::new(Vec::new());
+
+        let mut watcher =
+            Watcher::builder(source(documents.into_iter().map(Some).collect()))
+                .baseline_fixed()
+                .wait(|status, _| {
+                    if status.polls == 2 {
+                        Flow::Restart
+                    } else {
+                        Flow::Continue
+                    }
+                })
+                .on_initial(|_, json| initial.borrow_mut().push(json.clone()))
+                .on_diff(|_, prev, current, _| {
+                    seen.borrow_mut().push((prev.clone(), current.clone()));
+                    Flow::Continue
+                })
+                .on_error(stop_on_error)
+                .build();
+        watcher.run();
+        drop(watcher);
+
+        // The restart before the third poll makes 3 the new baseline.
+        assert_eq!(*initial.borrow(), vec![json!(1), json!(3)]);
+        assert_eq!(
+            seen.into_inner(),
+            vec![
+                (Some(json!(1)), Some(json!(2))),
+                (Some(json!(3)), Some(json!(4))),
+            ]
+        );
+    }
+}